@@ -1,69 +1,400 @@
+use std::collections::HashMap;
 use std::fmt::Display;
 use std::hint::black_box;
+use std::time::{Duration, Instant};
 
-const WINNING_PATTERNS: [[usize; 3]; 8] = [
-    [0, 1, 2],
-    [3, 4, 5],
-    [6, 7, 8],
-    [0, 3, 6],
-    [1, 4, 7],
-    [2, 5, 8],
-    [0, 4, 8],
-    [2, 4, 6],
+/// Alpha-beta window bound (the effective "+/- infinity" passed to the root
+/// of a search), comfortably wider than `WIN_SCORE` while staying clear of
+/// `i8::MIN`/`MAX` so repeated negation never overflows.
+const SEARCH_BOUND: i8 = 120;
+
+/// Magnitude of a confirmed win or loss. Strictly greater than
+/// `EVAL_BOUND`, so a depth-limited search can never prefer a
+/// merely-good-looking non-terminal position (scored by `evaluate`) over an
+/// actual forced win.
+const WIN_SCORE: i8 = 100;
+
+/// Magnitude `evaluate`'s heuristic output is clamped to. Strictly below
+/// `WIN_SCORE` (see above).
+const EVAL_BOUND: i8 = 50;
+
+/// Directions to scan for winning lines: right, down, and the two diagonals.
+const DIRECTIONS: [(isize, isize); 4] = [(0, 1), (1, 0), (1, 1), (1, -1)];
+
+/// Builds the winning lines for an `N`x`N` board with a win length of `K`: for
+/// every cell and every direction, the `K` cells starting there if they stay on
+/// the board.
+fn winning_lines<const N: usize, const K: usize>() -> Vec<[usize; K]> {
+    let mut lines = Vec::new();
+    for r in 0..N as isize {
+        for c in 0..N as isize {
+            for (dr, dc) in DIRECTIONS {
+                let mut line = [0usize; K];
+                let mut on_board = true;
+                for (i, slot) in line.iter_mut().enumerate() {
+                    let rr = r + dr * i as isize;
+                    let cc = c + dc * i as isize;
+                    if rr < 0 || rr >= N as isize || cc < 0 || cc >= N as isize {
+                        on_board = false;
+                        break;
+                    }
+                    *slot = rr as usize * N + cc as usize;
+                }
+                if on_board {
+                    lines.push(line);
+                }
+            }
+        }
+    }
+    lines
+}
+
+/// A symmetry, expressed as a map from `(board size, row, col)` to the
+/// transformed `(row, col)`.
+type SymmetryTransform = fn(usize, usize, usize) -> (usize, usize);
+
+/// The 8 dihedral symmetries of a square grid (4 rotations x reflection).
+const SYMMETRY_TRANSFORMS: [SymmetryTransform; 8] = [
+    |_, r, c| (r, c),
+    |n, r, c| (c, n - 1 - r),
+    |n, r, c| (n - 1 - r, n - 1 - c),
+    |n, r, c| (n - 1 - c, r),
+    |n, r, c| (r, n - 1 - c),
+    |n, r, c| (n - 1 - r, c),
+    |_, r, c| (c, r),
+    |n, r, c| (n - 1 - c, n - 1 - r),
 ];
 
+/// For each of the 8 symmetries, the cell each board index maps to under that
+/// symmetry, i.e. `image[s][i]` is where cell `i` lands under symmetry `s`.
+fn build_symmetries<const N: usize>() -> Vec<Vec<usize>> {
+    SYMMETRY_TRANSFORMS
+        .iter()
+        .map(|transform| {
+            (0..N * N)
+                .map(|i| {
+                    let (r, c) = (i / N, i % N);
+                    let (nr, nc) = transform(N, r, c);
+                    nr * N + nc
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Moves the set bits of `bits` according to `image` (`image[i]` is the
+/// destination of bit `i`).
+fn permute_bits(bits: u64, image: &[usize]) -> u64 {
+    let mut out = 0u64;
+    for (old, &new) in image.iter().enumerate() {
+        if bits & (1 << old) != 0 {
+            out |= 1 << new;
+        }
+    }
+    out
+}
+
 #[derive(Copy, Clone)]
 enum Player {
     X,
     O,
 }
 
-struct State {
-    board: [Option<Player>; 9],
+/// Bound kind recorded for a transposition-table entry, following the usual
+/// fail-soft alpha-beta convention.
+#[derive(Copy, Clone)]
+enum TTFlag {
+    Exact,
+    LowerBound,
+    UpperBound,
+}
+
+#[derive(Copy, Clone)]
+struct TTEntry {
+    /// Remaining depth the entry was searched to; an entry is only usable
+    /// for a query that needs no more depth than this.
+    depth: u32,
+    value: i8,
+    flag: TTFlag,
+}
+
+/// A two-player, zero-sum, perfect-information game that the generic negamax
+/// engine below can search. Scores are from the perspective of the player to
+/// move, as is conventional for negamax.
+trait Game {
+    type Move: Copy;
+
+    /// Legal moves from the current position.
+    fn moves(&self) -> impl Iterator<Item = Self::Move>;
+    fn apply(&mut self, m: Self::Move);
+    fn undo(&mut self, m: Self::Move);
+    /// `Some(score)` if the position is terminal, from the perspective of the
+    /// player to move.
+    fn terminal_score(&self) -> Option<i8>;
+    /// Heuristic estimate of a non-terminal position, from the perspective of
+    /// the player to move. Used when the search runs out of depth.
+    fn evaluate(&self) -> i8;
+    /// Canonical hash of the current position, used as the transposition
+    /// table key. Positions that are equivalent up to the game's symmetries
+    /// should return the same hash.
+    fn hash(&self) -> u128;
+}
+
+/// Fail-soft alpha-beta negamax over any [`Game`], searched to a full depth,
+/// with a transposition table keyed on [`Game::hash`] shared across the whole
+/// search tree.
+fn search<G: Game>(game: &mut G, lower: i8, upper: i8) -> (i8, Option<G::Move>) {
+    let mut tt = HashMap::new();
+    negamax(game, lower, upper, u32::MAX, &mut tt, None)
+        .expect("an unbounded search (deadline: None) never aborts")
+}
+
+/// Fail-soft alpha-beta negamax, cut off at `depth` plies with [`Game::evaluate`]
+/// standing in for the true score. `tt` persists across sibling calls (and,
+/// for iterative deepening, across calls with increasing `depth`) so
+/// transposed positions are only searched once per depth.
+///
+/// Returns `None` if `deadline` passes before the search below this node
+/// completes, at any depth - not just a heuristic cutoff at `depth == 0` -
+/// so a caller can tell a genuine result apart from one tainted by running
+/// out of time partway through the tree, and discard it instead of treating
+/// it as if this depth had finished.
+fn negamax<G: Game>(
+    game: &mut G,
+    mut lower: i8,
+    mut upper: i8,
+    depth: u32,
+    tt: &mut HashMap<u128, TTEntry>,
+    deadline: Option<Instant>,
+) -> Option<(i8, Option<G::Move>)> {
+    if let Some(score) = game.terminal_score() {
+        return Some((-score, None));
+    }
+    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        return None;
+    }
+    if depth == 0 {
+        return Some((-game.evaluate(), None));
+    }
+
+    let hash = game.hash();
+    let alpha_orig = lower;
+    let usable_entry = tt.get(&hash).copied().filter(|entry| entry.depth >= depth);
+    if let Some(entry) = usable_entry {
+        match entry.flag {
+            TTFlag::Exact => return Some((-entry.value, None)),
+            TTFlag::LowerBound if entry.value > lower => lower = entry.value,
+            TTFlag::UpperBound if entry.value < upper => upper = entry.value,
+            _ => (),
+        }
+        if lower >= upper {
+            return Some((-entry.value, None));
+        }
+    }
+
+    let moves: Vec<G::Move> = game.moves().collect();
+    let mut best_move = None;
+    let mut best_score = -SEARCH_BOUND;
+    for m in moves {
+        game.apply(m);
+        let result = negamax(game, -upper, -lower, depth - 1, tt, deadline);
+        game.undo(m);
+        let (score, _) = result?;
+        if score > best_score {
+            best_score = score;
+            best_move = Some(m);
+            if score > lower {
+                lower = score;
+                if score >= upper {
+                    break;
+                }
+            }
+        }
+    }
+
+    let flag = if best_score <= alpha_orig {
+        TTFlag::UpperBound
+    } else if best_score >= upper {
+        TTFlag::LowerBound
+    } else {
+        TTFlag::Exact
+    };
+    tt.insert(
+        hash,
+        TTEntry {
+            depth,
+            value: best_score,
+            flag,
+        },
+    );
+
+    Some((-best_score, best_move))
+}
+
+/// Game state for an `N`x`N` board where `K` in a row wins, e.g. `State::<3, 3>`
+/// for classic tic-tac-toe.
+///
+/// The board is stored as two bitboards (`x`/`o`, one bit per cell) rather than
+/// a cell array, so a win check is a handful of `u64` masks-and-compares
+/// instead of a scan. This caps `N * N` at 64 cells, which rules out larger
+/// gomoku-style boards such as the 15x15, 5-in-a-row example this type was
+/// originally generalized for — that would need a wider (or multi-word)
+/// board representation than the one chosen here.
+struct State<const N: usize, const K: usize> {
+    x: u64,
+    o: u64,
     turn: Player,
+    /// Precomputed winning lines as bitmasks, so `absolute_score` doesn't
+    /// rebuild them on every call.
+    lines: Vec<u64>,
+    /// The 8 dihedral symmetries of the board, precomputed so `canonical`
+    /// doesn't rebuild them on every call.
+    symmetries: Vec<Vec<usize>>,
 }
 
-impl State {
-    const fn new() -> Self {
+impl<const N: usize, const K: usize> State<N, K> {
+    fn new() -> Self {
+        assert!(N * N <= 64, "board must fit in a 64-bit bitboard");
+        let lines = winning_lines::<N, K>()
+            .into_iter()
+            .map(|pattern| pattern.iter().fold(0u64, |mask, &pos| mask | (1 << pos)))
+            .collect();
         Self {
-            board: [None; 9],
+            x: 0,
+            o: 0,
             turn: Player::X,
+            lines,
+            symmetries: build_symmetries::<N>(),
+        }
+    }
+
+    fn full_mask(&self) -> u64 {
+        if N * N == 64 {
+            u64::MAX
+        } else {
+            (1u64 << (N * N)) - 1
         }
     }
 
+    /// Canonical key for the current board: encode `x`/`o` as 64 bits each
+    /// (packed into one `u128`) under every one of the 8 board symmetries,
+    /// and take the minimum. Equivalent-up-to-symmetry boards always agree on
+    /// this key, which lets the transposition table collapse them into one
+    /// entry. `x` and `o` are each already guaranteed to fit in 64 bits by
+    /// the `N * N <= 64` assertion in `new`, so no further size check is
+    /// needed here.
+    fn canonical(&self) -> u128 {
+        self.symmetries
+            .iter()
+            .map(|image| {
+                let x = permute_bits(self.x, image);
+                let o = permute_bits(self.o, image);
+                u128::from(x) | (u128::from(o) << 64)
+            })
+            .min()
+            .unwrap()
+    }
+
     fn best_move(&mut self) -> usize {
-        let (_, best_move) = self.search(-2, 2);
-        best_move
-    }
-
-    fn search(&mut self, mut lower: i8, upper: i8) -> (i8, usize) {
-        if let Some(score) = self.score() {
-            return (-score, 0);
-        }
-        let mut best_move = 0;
-        let mut best_score = -2;
-        for pos in 0..9 {
-            if self.board[pos].is_none() {
-                self.do_move(pos);
-                let (score, _) = self.search(-upper, -lower);
-                self.undo_move(pos);
-                if score > best_score {
-                    best_score = score;
-                    best_move = pos;
+        let (_, best_move) = search(self, -SEARCH_BOUND, SEARCH_BOUND);
+        best_move.expect("best_move called on a terminal position")
+    }
+
+    /// Iteratively deepening anytime search: runs depth 1, 2, 3, ... reusing
+    /// the transposition table for move ordering at each new depth, and
+    /// returns the best move found by the last depth that finished before
+    /// `budget` elapsed. A depth counts as finished only if every root move
+    /// searched it to completion; if `negamax` aborts partway through any
+    /// one of them (the `Some(...) = ... else` below), that whole depth's
+    /// result is discarded, not just the truncated move.
+    fn best_move_timed(&mut self, budget: Duration) -> usize {
+        let deadline = Instant::now() + budget;
+        let mut tt = HashMap::new();
+        let mut best = self
+            .moves()
+            .next()
+            .expect("best_move_timed called on a terminal position");
+
+        let mut depth: u32 = 1;
+        while Instant::now() < deadline {
+            let moves = self.ordered_moves(&tt);
+            let mut depth_best = None;
+            let mut depth_best_score = -SEARCH_BOUND;
+            let mut lower = -SEARCH_BOUND;
+            let mut timed_out = false;
+            for m in moves {
+                if Instant::now() >= deadline {
+                    timed_out = true;
+                    break;
+                }
+                self.apply(m);
+                let result = negamax(
+                    self,
+                    -SEARCH_BOUND,
+                    -lower,
+                    depth - 1,
+                    &mut tt,
+                    Some(deadline),
+                );
+                self.undo(m);
+                let Some((score, _)) = result else {
+                    timed_out = true;
+                    break;
+                };
+                if score > depth_best_score {
+                    depth_best_score = score;
+                    depth_best = Some(m);
                     if score > lower {
                         lower = score;
-                        if score >= upper {
+                        if score >= SEARCH_BOUND {
                             break;
                         }
                     }
                 }
             }
+            if timed_out {
+                break;
+            }
+            if let Some(m) = depth_best {
+                best = m;
+            }
+            depth += 1;
         }
-        return (-best_score, best_move);
+        best
+    }
+
+    /// Root moves ordered best-first by their cached transposition-table
+    /// value, so iterative deepening tends to search the previous
+    /// iteration's best move first at the next depth.
+    fn ordered_moves(&mut self, tt: &HashMap<u128, TTEntry>) -> Vec<usize> {
+        let mut moves: Vec<usize> = self.moves().collect();
+        moves.sort_by_key(|&m| {
+            self.apply(m);
+            let score = tt.get(&self.hash()).map(|entry| -entry.value);
+            self.undo(m);
+            std::cmp::Reverse(score.unwrap_or(i8::MIN))
+        });
+        moves
+    }
+
+    /// Counts open `K`-windows (winning lines not yet blocked by the
+    /// opponent) for X minus for O, as a cheap non-terminal heuristic.
+    fn absolute_heuristic(&self) -> i8 {
+        let open_for = |mine: u64, theirs: u64| {
+            self.lines
+                .iter()
+                .filter(|&&line| line & theirs == 0 && line & mine != 0)
+                .count() as i32
+        };
+        (open_for(self.x, self.o) - open_for(self.o, self.x))
+            .clamp(i32::from(-EVAL_BOUND) + 1, i32::from(EVAL_BOUND) - 1) as i8
     }
 
     fn do_move(&mut self, pos: usize) {
-        self.board[pos] = Some(self.turn);
+        match self.turn {
+            Player::X => self.x |= 1 << pos,
+            Player::O => self.o |= 1 << pos,
+        }
         self.turn = match self.turn {
             Player::X => Player::O,
             Player::O => Player::X,
@@ -71,10 +402,13 @@ impl State {
     }
 
     fn undo_move(&mut self, pos: usize) {
-        self.board[pos] = None;
         self.turn = match self.turn {
             Player::X => Player::O,
             Player::O => Player::X,
+        };
+        match self.turn {
+            Player::X => self.x &= !(1 << pos),
+            Player::O => self.o &= !(1 << pos),
         }
     }
 
@@ -89,48 +423,87 @@ impl State {
     }
 
     fn absolute_score(&self) -> Option<i8> {
-        for pattern in WINNING_PATTERNS.iter() {
-            match (
-                self.board[pattern[0]],
-                self.board[pattern[1]],
-                self.board[pattern[2]],
-            ) {
-                (Some(Player::X), Some(Player::X), Some(Player::X)) => return Some(1),
-                (Some(Player::O), Some(Player::O), Some(Player::O)) => return Some(-1),
-                _ => (),
+        for &line in &self.lines {
+            if self.x & line == line {
+                return Some(WIN_SCORE);
+            }
+            if self.o & line == line {
+                return Some(-WIN_SCORE);
             }
         }
-        if self.board.iter().all(|p| p.is_some()) {
+        if self.x | self.o == self.full_mask() {
             return Some(0);
         }
         None
     }
 }
 
-impl Display for State {
+impl<const N: usize, const K: usize> Game for State<N, K> {
+    type Move = usize;
+
+    fn moves(&self) -> impl Iterator<Item = usize> {
+        let occupied = self.x | self.o;
+        (0..N * N).filter(move |&pos| occupied & (1 << pos) == 0)
+    }
+
+    fn apply(&mut self, m: usize) {
+        self.do_move(m);
+    }
+
+    fn undo(&mut self, m: usize) {
+        self.undo_move(m);
+    }
+
+    fn terminal_score(&self) -> Option<i8> {
+        self.score()
+    }
+
+    fn evaluate(&self) -> i8 {
+        let heuristic = self.absolute_heuristic();
+        if let Player::X = self.turn {
+            heuristic
+        } else {
+            -heuristic
+        }
+    }
+
+    fn hash(&self) -> u128 {
+        self.canonical()
+    }
+}
+
+impl<const N: usize, const K: usize> Display for State<N, K> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "---+---+---")?;
-        for row in self.board.chunks(3) {
-            writeln!(
-                f,
-                "{}",
-                row.iter()
-                    .map(|p| match p {
-                        Some(Player::X) => " X ",
-                        Some(Player::O) => " O ",
-                        None => "   ",
-                    })
-                    .collect::<Vec<_>>()
-                    .join("|")
-            )?;
-            writeln!(f, "---+---+---")?;
+        let separator = "---+".repeat(N);
+        writeln!(f, "{}", separator)?;
+        for row in 0..N {
+            let cells = (0..N)
+                .map(|col| {
+                    let pos = row * N + col;
+                    if self.x & (1 << pos) != 0 {
+                        " X "
+                    } else if self.o & (1 << pos) != 0 {
+                        " O "
+                    } else {
+                        "   "
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("|");
+            writeln!(f, "{}", cells)?;
+            writeln!(f, "{}", separator)?;
         }
         Ok(())
     }
 }
 
+/// Board size the interactive game in `main` (and the benchmarks below) run
+/// against: classic tic-tac-toe.
+const BOARD_N: usize = 3;
+const BOARD_K: usize = 3;
+
 fn benchmark() {
-    let mut state = State::new();
+    let mut state = State::<BOARD_N, BOARD_K>::new();
     let samples = 100;
     let now = std::time::Instant::now();
     for _ in 0..samples {
@@ -141,15 +514,23 @@ fn benchmark() {
         "Best move found in an average of {}ms",
         (now.elapsed().as_millis() as f64) / (samples as f64)
     );
+
+    let mut state = State::<BOARD_N, BOARD_K>::new();
+    let now = std::time::Instant::now();
+    state.best_move_timed(Duration::from_millis(50));
+    println!(
+        "Anytime best move found in {}ms with a 50ms budget",
+        now.elapsed().as_millis()
+    );
 }
 
 fn main() {
     benchmark();
 
-    let mut state = State::new();
+    let mut state = State::<BOARD_N, BOARD_K>::new();
     println!("\n{}", state);
     while state.score().is_none() {
-        println!("Enter move [0, 9): ");
+        println!("Enter move [0, {}): ", BOARD_N * BOARD_N);
         let mut input = String::new();
         if std::io::stdin().read_line(&mut input).is_err() {
             continue;
@@ -159,7 +540,7 @@ fn main() {
             continue;
         }
         let pos = input.unwrap();
-        if pos >= 9 || state.board[pos].is_some() {
+        if pos >= BOARD_N * BOARD_N || (state.x | state.o) & (1 << pos) != 0 {
             continue;
         }
 
@@ -177,9 +558,24 @@ fn main() {
     }
 
     match state.score() {
-        Some(1) => println!("You win!"),
-        Some(-1) => println!("You lose!"),
+        Some(WIN_SCORE) => println!("You win!"),
+        Some(score) if score == -WIN_SCORE => println!("You lose!"),
         Some(0) => println!("Draw!"),
         _ => (),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Center is the unique drawing reply to a corner open on 3x3 - a pin
+    /// for the transposition table, whose TT-hit paths once returned a
+    /// value with the wrong sign and picked an edge instead.
+    #[test]
+    fn best_move_responds_to_corner_with_center() {
+        let mut state = State::<3, 3>::new();
+        state.do_move(0);
+        assert_eq!(state.best_move(), 4);
+    }
+}